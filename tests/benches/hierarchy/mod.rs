@@ -0,0 +1,88 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+pub mod txn;
+
+use tikv::storage::kv::Engine;
+
+pub const DEFAULT_ITERATIONS: usize = 1_000;
+
+pub trait EngineFactory<E: Engine>: Copy + Clone + Send + 'static {
+    fn build(&self) -> E;
+}
+
+#[derive(Clone)]
+pub struct BenchConfig<F> {
+    /// Number of keys written by a single transaction. Benches that build a
+    /// transaction from scratch (e.g. `setup_prewrite`) use this instead of
+    /// the fixed `DEFAULT_ITERATIONS`.
+    pub keys_per_txn: usize,
+    pub key_length: usize,
+    pub value_length: usize,
+    pub engine_factory: F,
+    /// Number of worker threads to use for benches that exercise concurrent
+    /// access to a shared `ConcurrencyManager`, e.g. `txn_prewrite_contended`.
+    pub concurrency: usize,
+}
+
+/// An inclusive `[low, high]` range expanded into `steps` evenly spaced
+/// values, used by `BenchMatrix` to sweep a `BenchConfig` dimension.
+#[derive(Clone, Copy)]
+pub struct Range {
+    pub low: usize,
+    pub high: usize,
+    pub steps: usize,
+}
+
+impl Range {
+    /// A "range" that only ever produces a single, fixed value.
+    pub fn fixed(value: usize) -> Range {
+        Range {
+            low: value,
+            high: value,
+            steps: 1,
+        }
+    }
+
+    fn values(&self) -> Vec<usize> {
+        if self.steps <= 1 || self.high <= self.low {
+            return vec![self.low];
+        }
+        let span = self.high - self.low;
+        let steps = self.steps - 1;
+        (0..self.steps)
+            .map(|i| self.low + i * span / steps)
+            .collect()
+    }
+}
+
+/// Sweeps `keys_per_txn`, `key_length` and `value_length` over independent
+/// `Range`s and expands them into the cartesian product of `BenchConfig`s,
+/// so callers can profile how prewrite/commit/rollback scale with
+/// transaction size and payload size without hand-writing each config.
+pub struct BenchMatrix<F> {
+    pub keys_per_txn: Range,
+    pub key_length: Range,
+    pub value_length: Range,
+    pub engine_factory: F,
+    pub concurrency: usize,
+}
+
+impl<F: Copy> BenchMatrix<F> {
+    pub fn expand(&self) -> Vec<BenchConfig<F>> {
+        let mut configs = Vec::new();
+        for keys_per_txn in self.keys_per_txn.values() {
+            for key_length in self.key_length.values() {
+                for value_length in self.value_length.values() {
+                    configs.push(BenchConfig {
+                        keys_per_txn,
+                        key_length,
+                        value_length,
+                        engine_factory: self.engine_factory,
+                        concurrency: self.concurrency,
+                    });
+                }
+            }
+        }
+        configs
+    }
+}
@@ -1,5 +1,9 @@
 // Copyright 2018 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
 use criterion::{black_box, BatchSize, Bencher, Criterion};
 use kvproto::kvrpcpb::Context;
 use test_util::KvGenerator;
@@ -10,7 +14,7 @@ use tikv::storage::{
 };
 use txn_types::{Key, Mutation, TimeStamp};
 
-use super::{BenchConfig, EngineFactory, DEFAULT_ITERATIONS};
+use super::{BenchConfig, EngineFactory};
 
 fn setup_prewrite<E, F>(
     engine: &E,
@@ -28,7 +32,8 @@ where
     let cm = ConcurrencyManager::new(start_ts);
     let mut txn = MvccTxn::new(snapshot, start_ts, true, cm);
 
-    let kvs = KvGenerator::new(config.key_length, config.value_length).generate(DEFAULT_ITERATIONS);
+    let kvs =
+        KvGenerator::new(config.key_length, config.value_length).generate(config.keys_per_txn);
     for (k, v) in &kvs {
         txn.prewrite(
             Mutation::Put((Key::from_raw(&k), v.clone())),
@@ -55,7 +60,7 @@ fn txn_prewrite<E: Engine, F: EngineFactory<E>>(b: &mut Bencher, config: &BenchC
         || {
             let mutations: Vec<(Mutation, Vec<u8>)> =
                 KvGenerator::new(config.key_length, config.value_length)
-                    .generate(DEFAULT_ITERATIONS)
+                    .generate(config.keys_per_txn)
                     .iter()
                     .map(|(k, v)| (Mutation::Put((Key::from_raw(&k), v.clone())), k.clone()))
                     .collect();
@@ -142,7 +147,7 @@ fn txn_rollback_non_prewrote<E: Engine, F: EngineFactory<E>>(
     b.iter_batched(
         || {
             let kvs = KvGenerator::new(config.key_length, config.value_length)
-                .generate(DEFAULT_ITERATIONS);
+                .generate(config.keys_per_txn);
             let keys: Vec<Key> = kvs.iter().map(|(k, _)| Key::from_raw(&k)).collect();
             keys
         },
@@ -159,6 +164,351 @@ fn txn_rollback_non_prewrote<E: Engine, F: EngineFactory<E>>(
     )
 }
 
+/// Spawns `config.concurrency` worker threads that each prewrite
+/// `config.keys_per_txn` keys through a *shared* `ConcurrencyManager`, all
+/// starting together via a `Barrier`. When `disjoint` is true every thread
+/// gets its own key range, so no thread ever waits on another's memory lock;
+/// when false all threads target the same keyspace, exercising the
+/// lock-table contention and `WriteConflict` fast path. Returns the wall
+/// time of the slowest thread, i.e. the elapsed time of the whole parallel
+/// region.
+fn txn_prewrite_contended<E, F>(
+    engine: &Arc<E>,
+    config: &BenchConfig<F>,
+    cm: &ConcurrencyManager,
+    disjoint: bool,
+) -> Duration
+where
+    E: Engine,
+    F: EngineFactory<E>,
+{
+    let concurrency = config.concurrency.max(1);
+    let barrier = Arc::new(Barrier::new(concurrency));
+    let shared_kvs =
+        KvGenerator::new(config.key_length, config.value_length).generate(config.keys_per_txn);
+
+    let handles: Vec<_> = (0..concurrency)
+        .map(|tid| {
+            let engine = engine.clone();
+            let cm = cm.clone();
+            let barrier = barrier.clone();
+            let kvs: Vec<(Vec<u8>, Vec<u8>)> = shared_kvs
+                .iter()
+                .cloned()
+                .map(|(k, v)| {
+                        if disjoint {
+                            let mut key = format!("t{}-", tid).into_bytes();
+                            key.extend_from_slice(&k);
+                            (key, v)
+                        } else {
+                            (k, v)
+                        }
+                    })
+                    .collect();
+
+            thread::spawn(move || {
+                let ctx = Context::default();
+                barrier.wait();
+                let start = Instant::now();
+                for (k, v) in kvs {
+                    let snapshot = engine.snapshot(&ctx).unwrap();
+                    let mut txn = mvcc::MvccTxn::new(snapshot, 1.into(), true, cm.clone());
+                    let res = txn.prewrite(
+                        Mutation::Put((Key::from_raw(&k), v)),
+                        &k,
+                        &None,
+                        false,
+                        0,
+                        0,
+                        TimeStamp::default(),
+                    );
+                    // Under the overlapping distribution a losing thread hits a
+                    // memory-lock or WriteConflict error; that is the cost this
+                    // bench is meant to capture, so keep going instead of
+                    // unwrapping.
+                    if res.is_ok() {
+                        let write_data = WriteData::from_modifies(txn.into_modifies());
+                        let _ = black_box(engine.write(&ctx, write_data));
+                    }
+                }
+                start.elapsed()
+            })
+        })
+        .collect();
+
+    handles
+        .into_iter()
+        .map(|h| h.join().unwrap())
+        .max()
+        .unwrap_or_default()
+}
+
+fn txn_prewrite_contended_disjoint<E: Engine, F: EngineFactory<E>>(
+    b: &mut Bencher,
+    config: &BenchConfig<F>,
+) {
+    let engine = Arc::new(config.engine_factory.build());
+    b.iter_custom(|iters| {
+        let mut elapsed = Duration::default();
+        for i in 0..iters {
+            let cm = ConcurrencyManager::new(i.into());
+            elapsed += txn_prewrite_contended(&engine, config, &cm, true);
+        }
+        elapsed
+    })
+}
+
+fn txn_prewrite_contended_overlapping<E: Engine, F: EngineFactory<E>>(
+    b: &mut Bencher,
+    config: &BenchConfig<F>,
+) {
+    let engine = Arc::new(config.engine_factory.build());
+    b.iter_custom(|iters| {
+        let mut elapsed = Duration::default();
+        for i in 0..iters {
+            let cm = ConcurrencyManager::new(i.into());
+            elapsed += txn_prewrite_contended(&engine, config, &cm, false);
+        }
+        elapsed
+    })
+}
+
+// `txn_prewrite_build` and `txn_commit_build` time the whole
+// `MvccTxn::prewrite`/`MvccTxn::commit` calls plus `into_modifies`, without
+// `engine.write`. Inside each call the conflict/constraint check (or the lock
+// lookup) and the building of the CF modifications are timed together, not
+// separately. `txn_commit_write` times only the `engine.write` of the commit
+// modifications; no bench times the `engine.write` of prewrite modifications
+// on its own.
+
+fn txn_prewrite_build<E: Engine, F: EngineFactory<E>>(b: &mut Bencher, config: &BenchConfig<F>) {
+    let engine = config.engine_factory.build();
+    let ctx = Context::default();
+    let cm = ConcurrencyManager::new(1.into());
+    b.iter_batched(
+        || {
+            let kvs = KvGenerator::new(config.key_length, config.value_length)
+                .generate(config.keys_per_txn);
+            let snapshot = engine.snapshot(&ctx).unwrap();
+            let txn = mvcc::MvccTxn::new(snapshot, 1.into(), true, cm.clone());
+            (txn, kvs)
+        },
+        |(mut txn, kvs)| {
+            for (k, v) in kvs {
+                txn.prewrite(
+                    Mutation::Put((Key::from_raw(&k), v)),
+                    &k,
+                    &None,
+                    false,
+                    0,
+                    0,
+                    TimeStamp::default(),
+                )
+                .unwrap();
+            }
+            black_box(txn.into_modifies());
+        },
+        BatchSize::SmallInput,
+    )
+}
+
+fn txn_commit_build<E: Engine, F: EngineFactory<E>>(b: &mut Bencher, config: &BenchConfig<F>) {
+    let engine = config.engine_factory.build();
+    let ctx = Context::default();
+    let cm = ConcurrencyManager::new(1.into());
+    b.iter_batched(
+        || {
+            let keys = setup_prewrite(&engine, &config, 1);
+            let snapshot = engine.snapshot(&ctx).unwrap();
+            let txn = mvcc::MvccTxn::new(snapshot, 1.into(), true, cm.clone());
+            (txn, keys)
+        },
+        |(mut txn, keys)| {
+            for key in keys {
+                txn.commit(key, 2.into()).unwrap();
+            }
+            black_box(txn.into_modifies());
+        },
+        BatchSize::SmallInput,
+    )
+}
+
+fn txn_commit_write<E: Engine, F: EngineFactory<E>>(b: &mut Bencher, config: &BenchConfig<F>) {
+    let engine = config.engine_factory.build();
+    let ctx = Context::default();
+    let cm = ConcurrencyManager::new(1.into());
+    b.iter_batched(
+        || {
+            let keys = setup_prewrite(&engine, &config, 1);
+            let snapshot = engine.snapshot(&ctx).unwrap();
+            let mut txn = mvcc::MvccTxn::new(snapshot, 1.into(), true, cm.clone());
+            for key in keys {
+                txn.commit(key, 2.into()).unwrap();
+            }
+            WriteData::from_modifies(txn.into_modifies())
+        },
+        |write_data| {
+            black_box(engine.write(&ctx, write_data)).unwrap();
+        },
+        BatchSize::SmallInput,
+    )
+}
+
+/// Acquires a pessimistic lock on `config.keys_per_txn` fresh keys at
+/// `for_update_ts` and writes the resulting locks into the engine, mirroring
+/// `setup_prewrite` but for the pessimistic lifecycle.
+fn setup_pessimistic_lock<E, F>(
+    engine: &E,
+    config: &BenchConfig<F>,
+    for_update_ts: impl Into<TimeStamp>,
+) -> Vec<(Key, Vec<u8>)>
+where
+    E: Engine,
+    F: EngineFactory<E>,
+{
+    let ctx = Context::default();
+    let for_update_ts = for_update_ts.into();
+    let cm = ConcurrencyManager::new(for_update_ts);
+    let snapshot = engine.snapshot(&ctx).unwrap();
+    let mut txn = MvccTxn::new(snapshot, for_update_ts, true, cm);
+
+    let kvs =
+        KvGenerator::new(config.key_length, config.value_length).generate(config.keys_per_txn);
+    for (k, _) in &kvs {
+        txn.acquire_pessimistic_lock(
+            Key::from_raw(k),
+            k,
+            false,
+            0,
+            for_update_ts,
+            false,
+            TimeStamp::default(),
+        )
+        .unwrap();
+    }
+    let write_data = WriteData::from_modifies(txn.into_modifies());
+    let _ = engine.write(&ctx, write_data);
+    kvs.into_iter()
+        .map(|(k, v)| (Key::from_raw(&k), v))
+        .collect()
+}
+
+fn txn_acquire_pessimistic_lock<E: Engine, F: EngineFactory<E>>(
+    b: &mut Bencher,
+    config: &BenchConfig<F>,
+) {
+    let engine = config.engine_factory.build();
+    let ctx = Context::default();
+    let cm = ConcurrencyManager::new(1.into());
+    b.iter_batched(
+        || KvGenerator::new(config.key_length, config.value_length).generate(config.keys_per_txn),
+        |kvs| {
+            let snapshot = engine.snapshot(&ctx).unwrap();
+            let mut txn = mvcc::MvccTxn::new(snapshot, 1.into(), true, cm.clone());
+            for (k, _) in &kvs {
+                txn.acquire_pessimistic_lock(
+                    Key::from_raw(k),
+                    k,
+                    false,
+                    0,
+                    1.into(),
+                    false,
+                    TimeStamp::default(),
+                )
+                .unwrap();
+            }
+            let write_data = WriteData::from_modifies(txn.into_modifies());
+            black_box(engine.write(&ctx, write_data)).unwrap();
+        },
+        BatchSize::SmallInput,
+    )
+}
+
+/// Acquires a pessimistic lock on keys that another transaction (`start_ts`
+/// 1) is already holding, so every call falls into the lock-conflict path
+/// instead of acquiring cleanly.
+fn txn_acquire_pessimistic_lock_conflict<E: Engine, F: EngineFactory<E>>(
+    b: &mut Bencher,
+    config: &BenchConfig<F>,
+) {
+    let engine = config.engine_factory.build();
+    let ctx = Context::default();
+    let cm = ConcurrencyManager::new(2.into());
+    b.iter_batched(
+        || setup_pessimistic_lock(&engine, &config, 1),
+        |kvs| {
+            let snapshot = engine.snapshot(&ctx).unwrap();
+            let mut txn = mvcc::MvccTxn::new(snapshot, 2.into(), true, cm.clone());
+            for (key, _) in kvs {
+                let _ = black_box(txn.acquire_pessimistic_lock(
+                    key,
+                    b"primary",
+                    false,
+                    0,
+                    2.into(),
+                    false,
+                    TimeStamp::default(),
+                ));
+            }
+        },
+        BatchSize::SmallInput,
+    )
+}
+
+fn txn_pessimistic_prewrite<E: Engine, F: EngineFactory<E>>(
+    b: &mut Bencher,
+    config: &BenchConfig<F>,
+) {
+    let engine = config.engine_factory.build();
+    let ctx = Context::default();
+    let cm = ConcurrencyManager::new(1.into());
+    b.iter_batched(
+        || setup_pessimistic_lock(&engine, &config, 1),
+        |kvs| {
+            let snapshot = engine.snapshot(&ctx).unwrap();
+            let mut txn = mvcc::MvccTxn::new(snapshot, 1.into(), true, cm.clone());
+            for (key, value) in kvs {
+                let primary = key.to_raw().unwrap();
+                txn.prewrite(
+                    Mutation::Put((key, value)),
+                    &primary,
+                    &None,
+                    true,
+                    0,
+                    1.into(),
+                    TimeStamp::default(),
+                )
+                .unwrap();
+            }
+            let write_data = WriteData::from_modifies(txn.into_modifies());
+            black_box(engine.write(&ctx, write_data)).unwrap();
+        },
+        BatchSize::SmallInput,
+    )
+}
+
+fn txn_pessimistic_rollback<E: Engine, F: EngineFactory<E>>(
+    b: &mut Bencher,
+    config: &BenchConfig<F>,
+) {
+    let engine = config.engine_factory.build();
+    let ctx = Context::default();
+    let cm = ConcurrencyManager::new(1.into());
+    b.iter_batched(
+        || setup_pessimistic_lock(&engine, &config, 1),
+        |kvs| {
+            let snapshot = engine.snapshot(&ctx).unwrap();
+            let mut txn = mvcc::MvccTxn::new(snapshot, 1.into(), true, cm.clone());
+            for (key, _) in kvs {
+                txn.pessimistic_rollback(key, 1.into()).unwrap();
+            }
+            let write_data = WriteData::from_modifies(txn.into_modifies());
+            black_box(engine.write(&ctx, write_data)).unwrap();
+        },
+        BatchSize::SmallInput,
+    )
+}
+
 pub fn bench_txn<E: Engine, F: EngineFactory<E>>(c: &mut Criterion, configs: &[BenchConfig<F>]) {
     c.bench_function_over_inputs("txn_prewrite", txn_prewrite, configs.to_owned());
     c.bench_function_over_inputs("txn_commit", txn_commit, configs.to_owned());
@@ -177,4 +527,37 @@ pub fn bench_txn<E: Engine, F: EngineFactory<E>>(c: &mut Criterion, configs: &[B
         txn_rollback_non_prewrote,
         configs.to_owned(),
     );
+    c.bench_function_over_inputs(
+        "txn_prewrite_contended_disjoint",
+        txn_prewrite_contended_disjoint,
+        configs.to_owned(),
+    );
+    c.bench_function_over_inputs(
+        "txn_prewrite_contended_overlapping",
+        txn_prewrite_contended_overlapping,
+        configs.to_owned(),
+    );
+    c.bench_function_over_inputs("txn_prewrite_build", txn_prewrite_build, configs.to_owned());
+    c.bench_function_over_inputs("txn_commit_build", txn_commit_build, configs.to_owned());
+    c.bench_function_over_inputs("txn_commit_write", txn_commit_write, configs.to_owned());
+    c.bench_function_over_inputs(
+        "txn_acquire_pessimistic_lock",
+        txn_acquire_pessimistic_lock,
+        configs.to_owned(),
+    );
+    c.bench_function_over_inputs(
+        "txn_acquire_pessimistic_lock_conflict",
+        txn_acquire_pessimistic_lock_conflict,
+        configs.to_owned(),
+    );
+    c.bench_function_over_inputs(
+        "txn_pessimistic_prewrite",
+        txn_pessimistic_prewrite,
+        configs.to_owned(),
+    );
+    c.bench_function_over_inputs(
+        "txn_pessimistic_rollback",
+        txn_pessimistic_rollback,
+        configs.to_owned(),
+    );
 }